@@ -24,110 +24,437 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
-use std::collections::HashMap;
+use std::cmp;
 use std::collections::HashSet;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::ops::Range;
+use std::time::Instant;
+
+/// Which totals main should print
+#[derive(PartialEq)]
+enum Mode {
+    Points,
+    Cards,
+    Both,
+}
+
+impl Mode {
+    fn from_string(string: &str) -> Mode {
+        match string {
+            "points" => Mode::Points,
+            "cards" => Mode::Cards,
+            "both" => Mode::Both,
+            _ => panic!("Unknown mode '{}', expected points, cards or both", string),
+        }
+    }
+}
+
+/// The winning numbers on a card
+enum Winning {
+    // Bit n is set if n is a winning number, for when they're all in 0..128
+    Bits(u128),
+    // Anything else
+    Hashed(HashSet<i32>),
+}
+
+impl Winning {
+    fn from_numbers(numbers: &[i32]) -> Winning {
+        if numbers.iter().all(|num| (0..128).contains(num)) {
+            let mut bits: u128 = 0;
+            for num in numbers {
+                bits |= 1 << num;
+            }
+            return Winning::Bits(bits);
+        }
+        return Winning::Hashed(numbers.iter().copied().collect());
+    }
+    fn contains(&self, num: i32) -> bool {
+        match self {
+            Winning::Bits(bits) => (0..128).contains(&num) && bits & (1 << num) != 0,
+            Winning::Hashed(set) => set.contains(&num),
+        }
+    }
+}
 
 struct Scratchcard {
     id: i32,
-    winning: HashSet<i32>,
+    winning: Winning,
     values: Vec<i32>,
 }
 
-impl Scratchcard {
-    fn from_string(string: &str) -> Scratchcard {
-        // Format is Game %d: Grab*, so split off the game name and the grabs
-        let split: Vec<&str> = string.split(':').collect();
-        // So we can find the id
-        let mut id: i32 = -1;
-        // Walk through the id string until we find the first digit, then parse the rest
-        for (idx, c) in split[0].char_indices() {
-            if c.is_ascii_digit() {
-                id = (split[0][idx..]).parse::<i32>().unwrap();
-                break;
+/// Why a line of the table couldn't be read
+#[derive(Debug)]
+enum ParseErrorKind {
+    MissingColon,
+    MissingBar,
+    BadId(String),
+    BadNumber(String),
+    // Ids have to go up by one from card to card
+    NonContiguousId { expected: i32, found: i32 },
+}
+
+#[derive(Debug)]
+struct ParseError {
+    line: usize,
+    kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ParseErrorKind::MissingColon => write!(f, "expected 'Card <id>:'"),
+            ParseErrorKind::MissingBar => write!(f, "expected '|' between the number lists"),
+            ParseErrorKind::BadId(id) => write!(f, "couldn't parse card id '{}'", id),
+            ParseErrorKind::BadNumber(num) => write!(f, "couldn't parse number '{}'", num),
+            ParseErrorKind::NonContiguousId { expected, found } => {
+                write!(f, "expected card {}, found card {}", expected, found)
             }
         }
-        // Now, we get the games
-        let split: Vec<&str> = split[1].split('|').collect();
-        // Map is pretty cool, huh
-        let mut winning = HashSet::<i32>::new();
-        for parsed in split[0].split_whitespace().map(str::parse::<i32>) {
-            match parsed {
-                Ok(int) => {
-                    winning.insert(int);
-                }
-                Err(_) => panic!("Couldn't parse"),
-            }
+    }
+}
+
+fn parse_numbers(string: &str) -> Result<Vec<i32>, ParseErrorKind> {
+    let mut numbers = Vec::<i32>::new();
+    for num in string.split_whitespace() {
+        match num.parse::<i32>() {
+            Ok(int) => numbers.push(int),
+            Err(_) => return Err(ParseErrorKind::BadNumber(num.to_string())),
         }
-        // Duplicate of above code, because I can't figure out how to handle the parse fail
-        let mut values = Vec::<i32>::new();
-        for parsed in split[1].split_whitespace().map(str::parse::<i32>) {
-            match parsed {
-                Ok(int) => {
-                    values.push(int);
-                }
-                Err(_) => panic!("Couldn't parse"),
+    }
+    return Ok(numbers);
+}
+
+impl Scratchcard {
+    /// Read a card, along with any winning numbers that are listed more than once
+    fn from_string(string: &str) -> Result<(Scratchcard, Vec<i32>), ParseErrorKind> {
+        // Format is Card %d: Winning* | Values*, so split off the card name and the numbers
+        let (name, numbers) = string.split_once(':').ok_or(ParseErrorKind::MissingColon)?;
+        // Strip the "Card" prefix to get at the id
+        let id_string = name.trim().trim_start_matches("Card").trim();
+        let id = match id_string.parse::<i32>() {
+            Ok(id) => id,
+            Err(_) => return Err(ParseErrorKind::BadId(id_string.to_string())),
+        };
+        let (winning, values) = numbers.split_once('|').ok_or(ParseErrorKind::MissingBar)?;
+        let winning = parse_numbers(winning)?;
+        let values = parse_numbers(values)?;
+
+        let mut seen = HashSet::<i32>::new();
+        let mut duplicates = Vec::<i32>::new();
+        for num in &winning {
+            if !seen.insert(*num) && !duplicates.contains(num) {
+                duplicates.push(*num);
             }
         }
-        return Scratchcard {
-            id: id,
-            winning: winning,
-            values: values,
+        let card = Scratchcard {
+            id,
+            winning: Winning::from_numbers(&winning),
+            values,
         };
+        return Ok((card, duplicates));
     }
     fn matches(&self) -> i32 {
         let mut matched = 0;
         for val in &self.values {
-            if self.winning.contains(&val) {
+            if self.winning.contains(*val) {
                 matched += 1;
             }
         }
         return matched;
     }
-    fn score(&self) -> i32 {
+    /// Points double with every match after the first. Returns None if they don't fit in a u64.
+    fn score(&self) -> Option<u64> {
         let matched = self.matches();
         if matched == 0 {
-            return 0;
+            return Some(0);
         }
-        return 1 << (matched - 1);
+        return 1u64.checked_shl((matched - 1) as u32);
     }
 }
 
-fn score(cards: &Vec<Scratchcard>, card: usize, acc: &mut HashMap<usize, i32>) -> i32 {
-    match acc.get(&card) {
-        Some(extra) => return *extra,
-        _ => (),
+/// Add up every card's points. Returns None if the total doesn't fit in a u64.
+fn total_points(cards: &[Scratchcard]) -> Option<u64> {
+    let mut total: u64 = 0;
+    for card in cards {
+        total = total.checked_add(card.score()?)?;
     }
+    return Some(total);
+}
 
-    let amount = cards[card].matches();
+/// Read every card in the table, warning about duplicate winning numbers.
+///
+/// Ids have to start wherever the first card does and go up by one, so the cascade can find cards
+/// by id.
+fn parse_cards(file: &str) -> Result<Vec<Scratchcard>, ParseError> {
+    let mut cards = Vec::<Scratchcard>::new();
+    for (idx, string) in file.lines().enumerate() {
+        let line = idx + 1;
+        if string.trim().is_empty() {
+            continue;
+        }
+        let (card, duplicates) =
+            Scratchcard::from_string(string).map_err(|kind| ParseError { line, kind })?;
+        if let Some(last) = cards.last() {
+            if card.id != last.id + 1 {
+                let kind = ParseErrorKind::NonContiguousId {
+                    expected: last.id + 1,
+                    found: card.id,
+                };
+                return Err(ParseError { line, kind });
+            }
+        }
+        for num in duplicates {
+            eprintln!(
+                "Warning: line {}: card {} lists winning number {} more than once",
+                line, card.id, num
+            );
+        }
+        cards.push(card);
+    }
+    return Ok(cards);
+}
 
-    let mut ret: i32 = 1;
-    // The next amount cards
-    for i in 0..(amount as usize) {
-        // Offset by 1 so we don't visit the same card again
-        let idx = card + i + 1;
-        ret += score(cards, idx, acc);
+/// Indices of the cards a card wins copies of.
+///
+/// A card with id `n` and `m` matches wins cards `n + 1` through `n + m`, and any ids past the
+/// last card are dropped.
+fn won_cards(cards: &[Scratchcard], card: usize) -> Range<usize> {
+    let first_id = cards[0].id as i64;
+    let last_id = cards[cards.len() - 1].id as i64;
+    let id = cards[card].id as i64;
+    let won_last = cmp::min(id + cards[card].matches() as i64, last_id);
+    return ((id + 1 - first_id) as usize)..((won_last + 1 - first_id) as usize);
+}
+
+/// Count how many cards we end up holding, including the originals.
+///
+/// Each card wins copies of the next `matches` cards, so the number of cards a single copy of card
+/// `i` turns into depends only on the cards after it. Walk from the bottom up so every card we win
+/// has already been counted, and clamp wins that would run off the end of the table.
+/// Returns None if the count doesn't fit in a u64.
+fn count_cards(cards: &[Scratchcard]) -> Option<u64> {
+    // How many cards a single copy of each card turns into
+    let mut yields = vec![0u64; cards.len()];
+    for card in (0..cards.len()).rev() {
+        let mut ret: u64 = 1;
+        for won in &yields[won_cards(cards, card)] {
+            ret = ret.checked_add(*won)?;
+        }
+        yields[card] = ret;
     }
 
-    // And memoize!
-    acc.insert(card, ret);
-    return ret;
+    let mut sum: u64 = 0;
+    for amount in yields {
+        sum = sum.checked_add(amount)?;
+    }
+    return Some(sum);
+}
+
+/// Where the copies of a single card came from
+struct CardTrace {
+    // How many copies we hold, including the original
+    copies: u64,
+    // Indices of earlier cards that won this one, and how many copies each handed out
+    from: Vec<(usize, u64)>,
+    // Copies held of this card and every card before it
+    total: u64,
+}
+
+/// Trace the copy cascade, recording how many copies of each card we end up holding.
+///
+/// Every copy of a card wins one more copy of each of the next `matches` cards, so walk top down
+/// and hand out copies as we go. Wins that would run off the end of the table are dropped.
+/// Returns None if any count doesn't fit in a u64.
+fn trace_cascade(cards: &[Scratchcard]) -> Option<Vec<CardTrace>> {
+    let mut trace: Vec<CardTrace> = (0..cards.len())
+        .map(|_| CardTrace {
+            copies: 1,
+            from: vec![],
+            total: 0,
+        })
+        .collect();
+    let mut total: u64 = 0;
+    for card in 0..cards.len() {
+        // Nothing after this can win more copies of it, so it's final
+        let copies = trace[card].copies;
+        total = total.checked_add(copies)?;
+        trace[card].total = total;
+
+        for won in &mut trace[won_cards(cards, card)] {
+            won.copies = won.copies.checked_add(copies)?;
+            won.from.push((card, copies));
+        }
+    }
+    return Some(trace);
+}
+
+fn copies_held(cards: &[Scratchcard]) -> Option<Vec<u64>> {
+    return Some(trace_cascade(cards)?.iter().map(|t| t.copies).collect());
+}
+
+fn print_trace_table(cards: &[Scratchcard], trace: &[CardTrace]) {
+    println!(
+        "{:>6} | {:>12} | {:>12} | Won from",
+        "Card", "Copies", "Total"
+    );
+    for (card, t) in cards.iter().zip(trace) {
+        let mut from: Vec<String> = vec!["original".to_string()];
+        for (idx, amount) in &t.from {
+            from.push(format!("{} x{}", cards[*idx].id, amount));
+        }
+        println!(
+            "{:>6} | {:>12} | {:>12} | {}",
+            card.id,
+            t.copies,
+            t.total,
+            from.join(", ")
+        );
+    }
+}
+
+/// Graphviz graph of which cards won copies of which, with edges labelled by copies handed out
+fn trace_to_dot(cards: &[Scratchcard], trace: &[CardTrace]) -> String {
+    let mut dot = String::from("digraph cascade {\n");
+    for (card, t) in cards.iter().zip(trace) {
+        dot += &format!(
+            "    card{} [label=\"Card {}\\n{} copies\"];\n",
+            card.id, card.id, t.copies
+        );
+    }
+    for (card, t) in cards.iter().zip(trace) {
+        for (idx, amount) in &t.from {
+            dot += &format!(
+                "    card{} -> card{} [label=\"{}\", weight={}];\n",
+                cards[*idx].id, card.id, amount, amount
+            );
+        }
+    }
+    dot += "}\n";
+    return dot;
+}
+
+/// Tiny xorshift generator, so benchmark decks are the same from run to run
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+    fn below(&mut self, bound: i32) -> i32 {
+        return (self.next() % bound as u64) as i32;
+    }
+}
+
+/// Time matching a generated deck with hash sets against the bitset representation
+fn bench(count: usize) {
+    let mut rng = XorShift(0x2023_1204);
+    let mut deck: Vec<(Vec<i32>, Vec<i32>)> = vec![];
+    for _ in 0..count {
+        let winning: Vec<i32> = (0..10).map(|_| 1 + rng.below(99)).collect();
+        let values: Vec<i32> = (0..25).map(|_| 1 + rng.below(99)).collect();
+        deck.push((winning, values));
+    }
+    println!("Generated {} cards", count);
+
+    let start = Instant::now();
+    let hashed: Vec<HashSet<i32>> = deck
+        .iter()
+        .map(|(winning, _)| winning.iter().copied().collect())
+        .collect();
+    let built = start.elapsed();
+    let mut hashed_matches: u64 = 0;
+    for (set, (_, values)) in hashed.iter().zip(&deck) {
+        hashed_matches += values.iter().filter(|val| set.contains(val)).count() as u64;
+    }
+    println!(
+        "HashSet: built in {:?}, matched in {:?}, {} matches",
+        built,
+        start.elapsed() - built,
+        hashed_matches
+    );
+
+    let start = Instant::now();
+    let bits: Vec<Winning> = deck
+        .iter()
+        .map(|(winning, _)| Winning::from_numbers(winning))
+        .collect();
+    let built = start.elapsed();
+    let mut bit_matches: u64 = 0;
+    for (set, (_, values)) in bits.iter().zip(&deck) {
+        bit_matches += values.iter().filter(|val| set.contains(**val)).count() as u64;
+    }
+    println!(
+        "Bitset:  built in {:?}, matched in {:?}, {} matches",
+        built,
+        start.elapsed() - built,
+        bit_matches
+    );
+    assert_eq!(hashed_matches, bit_matches, "Representations disagree");
 }
 
 fn main() {
-    // Read our calibration file and split it by line
-    let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
-    let mut data: Vec<&str> = file.split('\n').collect();
-    // Get rid of empty string at the end
-    while data.last().unwrap().len() == 0 {
-        data.pop();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        let count = match args.get(1) {
+            Some(count) => count.parse::<usize>().expect("Card count must be a number"),
+            None => 1_000_000,
+        };
+        bench(count);
+        return;
     }
 
-    let data: Vec<Scratchcard> = data.into_iter().map(Scratchcard::from_string).collect();
-    let mut sum = 0;
-    let mut acc = HashMap::<usize, i32>::new();
-    for i in 0..data.len() {
-        sum += score(&data, i, &mut acc);
+    let mut mode = Mode::Both;
+    let mut per_card = false;
+    let mut trace = false;
+    let mut dot = false;
+    for arg in &args {
+        match arg.as_str() {
+            "--per-card" => per_card = true,
+            "--trace" => trace = true,
+            "--dot" => dot = true,
+            _ => mode = Mode::from_string(arg),
+        }
+    }
+
+    // Read our calibration file
+    let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
+    let data = match parse_cards(&file) {
+        Ok(cards) => cards,
+        Err(err) => panic!("data.txt: {}", err),
+    };
+    if per_card {
+        let copies = copies_held(&data).expect("Number of scratchcards overflowed a u64");
+        for (card, held) in data.iter().zip(copies) {
+            println!(
+                "Card {}: {} matches, {} points, {} copies",
+                card.id,
+                card.matches(),
+                card.score().expect("Points for a card overflowed a u64"),
+                held
+            );
+        }
+    }
+    if trace || dot {
+        let cascade = trace_cascade(&data).expect("Number of scratchcards overflowed a u64");
+        if trace {
+            print_trace_table(&data, &cascade);
+        }
+        if dot {
+            print!("{}", trace_to_dot(&data, &cascade));
+        }
+    }
+    if mode != Mode::Cards {
+        let points = total_points(&data).expect("Number of points overflowed a u64");
+        println!("In total, {} points", points);
+    }
+    if mode != Mode::Points {
+        let sum = count_cards(&data).expect("Number of scratchcards overflowed a u64");
+        println!("In total, {} scratchcards", sum);
     }
-    println!("In total, {} scratchcards", sum);
 }
@@ -24,7 +24,18 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::ops::Range;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 fn parse_or_panic<T: std::str::FromStr>(string: &str) -> T {
     match string.parse::<T>() {
@@ -33,6 +44,7 @@ fn parse_or_panic<T: std::str::FromStr>(string: &str) -> T {
     }
 }
 
+#[derive(Clone, Copy)]
 struct Entry {
     dest: i64,
     source: i64,
@@ -40,18 +52,42 @@ struct Entry {
 }
 
 impl Entry {
-    fn read_from_string(string: &str) -> Entry {
+    fn read_from_string(string: &str) -> Result<Entry, String> {
+        let nums: Vec<&str> = string.split_whitespace().collect();
+        if nums.len() != 3 {
+            return Err(format!("expected 3 numbers, found {}", nums.len()));
+        }
         let mut read = [0, 0, 0];
-        let mut idx = 0;
-        for num in string.split_whitespace().map(parse_or_panic::<i64>) {
-            read[idx] = num;
-            idx += 1;
+        for (idx, num) in nums.into_iter().enumerate() {
+            read[idx] = match num.parse::<i64>() {
+                Ok(value) => value,
+                Err(_) => return Err(format!("couldn't parse '{}'", num)),
+            };
+        }
+        if read[2] < 0 {
+            return Err(format!("negative length {}", read[2]));
         }
-        return Entry {
+        return Ok(Entry {
             dest: read[0],
             source: read[1],
             length: read[2],
-        };
+        });
+    }
+    fn sources(&self) -> Range<i64> {
+        return self.source..(self.source + self.length);
+    }
+    fn offset(&self) -> i64 {
+        return self.dest - self.source;
+    }
+    fn destinations(&self) -> Range<i64> {
+        return self.dest..(self.dest + self.length);
+    }
+    /// The source id this entry maps to `id`, if it maps anything there
+    fn find_source(&self, id: i64) -> Option<i64> {
+        if !self.destinations().contains(&id) {
+            return None;
+        }
+        return Some(id - self.offset());
     }
     fn print(&self) {
         println!(
@@ -64,21 +100,274 @@ impl Entry {
     }
 }
 
-fn map_to<T>(string: &str, mapping: fn(&str) -> T) -> Vec<T> {
-    let mut singles: Vec<&str> = string
-        .split(":\n")
-        .skip(1)
-        .next()
-        .expect("")
-        .split('\n')
-        .collect();
-    if singles[singles.len() - 1].len() == 0 {
-        singles.pop();
+/// Something wrong with the almanac, and where
+#[derive(Clone)]
+enum Problem {
+    Malformed {
+        line: usize,
+        reason: String,
+    },
+    ZeroLength {
+        section: String,
+        line: usize,
+    },
+    // Two entries claim some of the same sources, so the first one in the file wins
+    OverlappingSources {
+        section: String,
+        line: usize,
+        other: usize,
+    },
+    // Two entries send ids to the same place, so the map isn't one-to-one
+    OverlappingDests {
+        section: String,
+        line: usize,
+        other: usize,
+    },
+}
+
+impl Problem {
+    fn line(&self) -> usize {
+        match self {
+            Problem::Malformed { line, .. } => *line,
+            Problem::ZeroLength { line, .. } => *line,
+            Problem::OverlappingSources { line, .. } => *line,
+            Problem::OverlappingDests { line, .. } => *line,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Malformed { line, reason } => write!(f, "line {}: {}", line, reason),
+            Problem::ZeroLength { section, line } => {
+                write!(f, "{}, line {}: entry has zero length", section, line)
+            }
+            Problem::OverlappingSources {
+                section,
+                line,
+                other,
+            } => write!(
+                f,
+                "{}, line {}: source range overlaps line {}",
+                section, line, other
+            ),
+            Problem::OverlappingDests {
+                section,
+                line,
+                other,
+            } => write!(
+                f,
+                "{}, line {}: destination range overlaps line {}",
+                section, line, other
+            ),
+        }
+    }
+}
+
+/// One "X-to-Y map:" section of the almanac
+struct AlmanacMap {
+    from: String,
+    to: String,
+    entries: Vec<Entry>,
+    // The line each entry came from
+    lines: Vec<usize>,
+    // The entries sorted by source with the gaps filled in, for find_sorted
+    sorted: Vec<Entry>,
+}
+
+impl AlmanacMap {
+    fn name(&self) -> String {
+        return format!("{}-to-{} map", self.from, self.to);
+    }
+
+    /// Check the map for zero-length entries and entries whose ranges overlap
+    fn validate(&self) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = vec![];
+        for (entry, line) in self.entries.iter().zip(&self.lines) {
+            if entry.length == 0 {
+                problems.push(Problem::ZeroLength {
+                    section: self.name(),
+                    line: *line,
+                });
+            }
+        }
+        for (line, other) in self.overlaps(Entry::sources) {
+            problems.push(Problem::OverlappingSources {
+                section: self.name(),
+                line,
+                other,
+            });
+        }
+        for (line, other) in self.overlaps(Entry::destinations) {
+            problems.push(Problem::OverlappingDests {
+                section: self.name(),
+                line,
+                other,
+            });
+        }
+        return problems;
+    }
+
+    /// Pairs of lines whose entries' ranges overlap, by whichever range `range_of` picks out
+    fn overlaps(&self, range_of: fn(&Entry) -> Range<i64>) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(Range<i64>, usize)> = self
+            .entries
+            .iter()
+            .map(range_of)
+            .zip(self.lines.iter().copied())
+            .filter(|(range, _)| !range.is_empty())
+            .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
+        let mut overlaps: Vec<(usize, usize)> = vec![];
+        for (idx, (range, line)) in ranges.iter().enumerate() {
+            for (other, other_line) in &ranges[(idx + 1)..] {
+                if other.start >= range.end {
+                    break;
+                }
+                overlaps.push((cmp::max(*line, *other_line), cmp::min(*line, *other_line)));
+            }
+        }
+        overlaps.sort();
+        return overlaps;
+    }
+}
+
+struct Almanac {
+    seeds: Vec<i64>,
+    maps: Vec<AlmanacMap>,
+    // Lines we couldn't make sense of
+    malformed: Vec<Problem>,
+}
+
+impl Almanac {
+    fn read_from_string(string: &str) -> Almanac {
+        let mut seeds: Option<Vec<i64>> = None;
+        let mut maps: Vec<AlmanacMap> = vec![];
+        let mut malformed: Vec<Problem> = vec![];
+        // Whether entry lines have a map to go in, so a bad header doesn't bury its entries in the
+        // previous map
+        let mut in_map = false;
+        for (idx, string) in string.lines().enumerate() {
+            let line = idx + 1;
+            let string = string.trim();
+            if string.is_empty() {
+                continue;
+            }
+            if let Some(numbers) = string.strip_prefix("seeds:") {
+                let parsed: Result<Vec<i64>, _> =
+                    numbers.split_whitespace().map(str::parse::<i64>).collect();
+                match parsed {
+                    Ok(parsed) if seeds.is_none() => seeds = Some(parsed),
+                    Ok(_) => malformed.push(Problem::Malformed {
+                        line,
+                        reason: "seeds listed more than once".to_string(),
+                    }),
+                    Err(_) => malformed.push(Problem::Malformed {
+                        line,
+                        reason: format!("couldn't parse seeds '{}'", numbers.trim()),
+                    }),
+                }
+                continue;
+            }
+            if let Some(header) = string.strip_suffix(':') {
+                in_map = false;
+                let name = header.strip_suffix(" map").unwrap_or("");
+                match name.split_once("-to-") {
+                    Some((from, to)) => {
+                        maps.push(AlmanacMap {
+                            from: from.to_string(),
+                            to: to.to_string(),
+                            entries: vec![],
+                            lines: vec![],
+                            sorted: vec![],
+                        });
+                        in_map = true;
+                    }
+                    None => malformed.push(Problem::Malformed {
+                        line,
+                        reason: format!("couldn't parse map header '{}'", string),
+                    }),
+                }
+                continue;
+            }
+            if !in_map {
+                malformed.push(Problem::Malformed {
+                    line,
+                    reason: "entry isn't in a map".to_string(),
+                });
+                continue;
+            }
+            let map = maps.last_mut().expect("");
+            match Entry::read_from_string(string) {
+                Ok(entry) => {
+                    map.entries.push(entry);
+                    map.lines.push(line);
+                }
+                Err(reason) => malformed.push(Problem::Malformed {
+                    line,
+                    reason: format!("{}: {}", map.name(), reason),
+                }),
+            }
+        }
+        for map in &mut maps {
+            map.sorted = sort_entries(&map.entries);
+        }
+        return Almanac {
+            seeds: seeds.expect("Almanac has no seeds line"),
+            maps,
+            malformed,
+        };
+    }
+
+    /// Every problem with the almanac, in the order they appear in the file
+    fn validate(&self) -> Vec<Problem> {
+        let mut problems = self.malformed.clone();
+        for map in &self.maps {
+            problems.extend(map.validate());
+        }
+        problems.sort_by_key(Problem::line);
+        return problems;
+    }
+
+    /// Find the maps that take a category to another, in the order to apply them.
+    ///
+    /// The maps can be in any order in the almanac. Returns None if there's no way to get there.
+    fn chain(&self, from: &str, to: &str) -> Option<Vec<&AlmanacMap>> {
+        // Which map we used to reach each category, so we can walk back once we find `to`
+        let mut reached_by = HashMap::<&str, Option<&AlmanacMap>>::new();
+        reached_by.insert(from, None);
+        let mut queue = VecDeque::<&str>::from([from]);
+        while let Some(category) = queue.pop_front() {
+            if category == to {
+                let mut chain: Vec<&AlmanacMap> = vec![];
+                let mut at = to;
+                while let Some(Some(map)) = reached_by.get(at) {
+                    chain.push(map);
+                    at = &map.from;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+            for map in &self.maps {
+                if map.from == category && !reached_by.contains_key(map.to.as_str()) {
+                    reached_by.insert(&map.to, Some(map));
+                    queue.push_back(&map.to);
+                }
+            }
+        }
+        return None;
+    }
+
+    fn chain_or_panic(&self, from: &str, to: &str) -> Vec<&AlmanacMap> {
+        match self.chain(from, to) {
+            Some(chain) => chain,
+            None => panic!("Almanac has no way to get from {} to {}", from, to),
+        }
     }
-    return singles.into_iter().map(mapping).collect();
 }
 
-fn find_from_entry(id: i64, map: &Vec<Entry>) -> i64 {
+fn find_from_entry(id: i64, map: &[Entry]) -> i64 {
     for entry in map {
         if !(entry.source..(entry.source + entry.length)).contains(&id) {
             continue;
@@ -88,77 +377,675 @@ fn find_from_entry(id: i64, map: &Vec<Entry>) -> i64 {
     return id;
 }
 
-fn generate_loc(
-        id: i64,
-        soil_map: &Vec<Entry>,
-        fert_map: &Vec<Entry>,
-        water_map: &Vec<Entry>,
-        light_map: &Vec<Entry>,
-        temp_map: &Vec<Entry>,
-        humid_map: &Vec<Entry>,
-        loc_map: &Vec<Entry>,
-) -> i64 {
-        let soil = find_from_entry(id, soil_map);
-        let fert = find_from_entry(soil, fert_map);
-        let water = find_from_entry(fert, water_map);
-        let light = find_from_entry(water, light_map);
-        let temp = find_from_entry(light, temp_map);
-        let humid = find_from_entry(temp, humid_map);
-        let loc = find_from_entry(humid, loc_map);
-        return loc;
+/// Sort a map by source and fill the gaps between entries with ones that map ids to themselves,
+/// so find_sorted can binary search it
+fn sort_entries(map: &[Entry]) -> Vec<Entry> {
+    let mut sorted: Vec<Entry> = vec![];
+    for entry in normalise(map) {
+        if let Some(last) = sorted.last() {
+            let end = last.sources().end;
+            if end < entry.source {
+                sorted.push(Entry {
+                    dest: end,
+                    source: end,
+                    length: entry.source - end,
+                });
+            }
+        }
+        sorted.push(entry);
+    }
+    return sorted;
+}
+
+/// Same as find_from_entry, but for a map from sort_entries
+fn find_sorted(id: i64, sorted: &[Entry]) -> i64 {
+    // The last entry starting at or before the id is the only one that can hold it
+    let idx = sorted.partition_point(|entry| entry.source <= id);
+    if idx == 0 {
+        return id;
+    }
+    let entry = &sorted[idx - 1];
+    if !entry.sources().contains(&id) {
+        return id;
     }
+    return id + entry.offset();
+}
 
-fn main() {
-    // Read our calibration file and split it by line
-    let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
-    let sections: Vec<&str> = file.split("\n\n").collect();
-
-    let seed_ranges: Vec<&str> = sections[0]
-        .split(":")
-        .skip(1)
-        .next()
-        .expect("")
-        .split_whitespace()
+/// Every id the map sends to `id`, since several can land on the same place
+fn find_sources(id: i64, map: &[Entry]) -> Vec<i64> {
+    let mut sources: Vec<i64> = map
+        .iter()
+        .filter_map(|entry| entry.find_source(id))
         .collect();
-    let seed_ranges: Vec<i64> = seed_ranges.into_iter().map(parse_or_panic::<i64>).collect();
-    let mut seeds: Vec<i64> = vec![];
-    let mut idx = 0;
-    println!("{}", seed_ranges.len());
-    while idx < seed_ranges.len() {
-        println!("{}", idx);
-        println!("Add: {}", seed_ranges[idx + 1]);
-        for i in seed_ranges[idx]..seed_ranges[idx + 1] {
-            seeds.push(i);
-        }
-        idx += 2;
-    }
-    println!("Done {}!", seeds.len());
-
-    let seed_to_soil = map_to::<Entry>(sections[1], Entry::read_from_string);
-    let soil_to_fert = map_to::<Entry>(sections[2], Entry::read_from_string);
-    let fert_to_watr = map_to::<Entry>(sections[3], Entry::read_from_string);
-    let watr_to_lght = map_to::<Entry>(sections[4], Entry::read_from_string);
-    let lght_to_temp = map_to::<Entry>(sections[5], Entry::read_from_string);
-    let temp_to_hmid = map_to::<Entry>(sections[6], Entry::read_from_string);
-    let hmid_to_locs = map_to::<Entry>(sections[7], Entry::read_from_string);
-
-    let seeds: Vec<i64> = seeds
-        .into_iter()
-        .map(|x: i64| {
-            generate_loc(
-                x,
-                &seed_to_soil,
-                &soil_to_fert,
-                &fert_to_watr,
-                &watr_to_lght,
-                &lght_to_temp,
-                &temp_to_hmid,
-                &hmid_to_locs,
-            )
-        })
+    // Unmapped ids map to themselves
+    sources.push(id);
+    // An entry only gets the id if no earlier entry claimed it first
+    sources.retain(|source| find_from_entry(*source, map) == id);
+    sources.sort();
+    sources.dedup();
+    return sources;
+}
+
+/// Every id at the start of the chain that ends up at `id`
+fn find_sources_through(id: i64, chain: &[&AlmanacMap]) -> Vec<i64> {
+    let mut ids = vec![id];
+    for map in chain.iter().rev() {
+        let mut sources: Vec<i64> = vec![];
+        for id in ids {
+            sources.extend(find_sources(id, &map.entries));
+        }
+        sources.sort();
+        sources.dedup();
+        ids = sources;
+    }
+    return ids;
+}
+
+/// Look an id up through every map in a chain
+fn generate_loc(id: i64, chain: &[&AlmanacMap]) -> i64 {
+    let mut id = id;
+    for map in chain {
+        id = find_sorted(id, &map.sorted);
+    }
+    return id;
+}
+
+/// generate_loc, scanning every entry of every map
+fn generate_loc_linear(id: i64, chain: &[&AlmanacMap]) -> i64 {
+    let mut id = id;
+    for map in chain {
+        id = find_from_entry(id, &map.entries);
+    }
+    return id;
+}
+
+/// Push whole ranges of ids through a map at once.
+///
+/// Each range is split against every entry's source range, with the overlapping piece mapped and
+/// the rest carried on to the next entry. Like find_from_entry, the first entry to claim an id wins,
+/// and anything no entry claims maps to itself.
+fn map_ranges(ranges: &[Range<i64>], map: &[Entry]) -> Vec<Range<i64>> {
+    let mut mapped: Vec<Range<i64>> = vec![];
+    let mut unmapped: Vec<Range<i64>> = ranges.to_vec();
+    for entry in map {
+        let source = entry.source..(entry.source + entry.length);
+        let offset = entry.dest - entry.source;
+        let mut rest: Vec<Range<i64>> = vec![];
+        for range in unmapped {
+            // Whatever comes before the entry
+            if range.start < source.start {
+                rest.push(range.start..cmp::min(range.end, source.start));
+            }
+            // The overlap, which gets moved
+            let start = cmp::max(range.start, source.start);
+            let end = cmp::min(range.end, source.end);
+            if start < end {
+                mapped.push((start + offset)..(end + offset));
+            }
+            // And whatever comes after
+            if range.end > source.end {
+                rest.push(cmp::max(range.start, source.end)..range.end);
+            }
+        }
+        unmapped = rest;
+    }
+    mapped.extend(unmapped);
+    return mapped;
+}
+
+/// The parts of a range that none of the claimed ranges cover
+fn subtract(range: Range<i64>, claimed: &[Range<i64>]) -> Vec<Range<i64>> {
+    let mut pieces = vec![range];
+    for claim in claimed {
+        let mut rest: Vec<Range<i64>> = vec![];
+        for piece in pieces {
+            if piece.start < claim.start {
+                rest.push(piece.start..cmp::min(piece.end, claim.start));
+            }
+            if piece.end > claim.end {
+                rest.push(cmp::max(piece.start, claim.end)..piece.end);
+            }
+        }
+        pieces = rest;
+    }
+    return pieces;
+}
+
+/// Sort a map by source, dropping entries that don't move anything and merging neighbours that
+/// move things by the same amount. Entries must not overlap.
+fn tidy(mut map: Vec<Entry>) -> Vec<Entry> {
+    map.retain(|entry| entry.length > 0 && entry.offset() != 0);
+    map.sort_by_key(|entry| entry.source);
+    let mut tidied: Vec<Entry> = vec![];
+    for entry in map {
+        match tidied.last_mut() {
+            Some(last) if last.sources().end == entry.source && last.offset() == entry.offset() => {
+                last.length += entry.length;
+            }
+            _ => tidied.push(entry),
+        }
+    }
+    return tidied;
+}
+
+/// Rewrite a map so its entries are sorted and don't overlap.
+///
+/// When entries overlap, the first one wins, the same as find_from_entry.
+fn normalise(map: &[Entry]) -> Vec<Entry> {
+    let mut claimed: Vec<Range<i64>> = vec![];
+    let mut entries: Vec<Entry> = vec![];
+    for entry in map {
+        for piece in subtract(entry.sources(), &claimed) {
+            entries.push(Entry {
+                dest: piece.start + entry.offset(),
+                source: piece.start,
+                length: piece.end - piece.start,
+            });
+        }
+        claimed.push(entry.sources());
+    }
+    return tidy(entries);
+}
+
+/// Split a range against a normalised map, giving each piece and how far the map moves it
+fn split_through(range: Range<i64>, map: &[Entry]) -> Vec<(Range<i64>, i64)> {
+    let mut pieces: Vec<(Range<i64>, i64)> = vec![];
+    let mut at = range.start;
+    for entry in map {
+        // Nothing left to split, which is also where an empty range stops
+        if at >= range.end {
+            break;
+        }
+        let sources = entry.sources();
+        if sources.end <= at {
+            continue;
+        }
+        if sources.start >= range.end {
+            break;
+        }
+        // A gap the map doesn't touch
+        if sources.start > at {
+            pieces.push((at..sources.start, 0));
+            at = sources.start;
+        }
+        let end = cmp::min(sources.end, range.end);
+        pieces.push((at..end, entry.offset()));
+        at = end;
+    }
+    if at < range.end {
+        pieces.push((at..range.end, 0));
+    }
+    return pieces;
+}
+
+/// Compose two maps into one that does the same as applying `first`, then `second`.
+///
+/// The result is sorted, doesn't overlap, and leaves out anything that maps to itself.
+fn compose(first: &[Entry], second: &[Entry]) -> Vec<Entry> {
+    let first = normalise(first);
+    let second = normalise(second);
+    let mut composed: Vec<Entry> = vec![];
+    // Ids first moves go wherever second sends their new value
+    for entry in &first {
+        let image = entry.dest..(entry.dest + entry.length);
+        for (piece, offset) in split_through(image, &second) {
+            composed.push(Entry {
+                dest: piece.start + offset,
+                source: piece.start - entry.offset(),
+                length: piece.end - piece.start,
+            });
+        }
+    }
+    // Everything else passes through first untouched, so only second applies
+    let claimed: Vec<Range<i64>> = first.iter().map(Entry::sources).collect();
+    for entry in &second {
+        for piece in subtract(entry.sources(), &claimed) {
+            composed.push(Entry {
+                dest: piece.start + entry.offset(),
+                source: piece.start,
+                length: piece.end - piece.start,
+            });
+        }
+    }
+    return tidy(composed);
+}
+
+/// Fold a chain of maps into a single map from the start of the chain to the end
+fn compose_chain(chain: &[&AlmanacMap]) -> Vec<Entry> {
+    let mut composed: Vec<Entry> = vec![];
+    for map in chain {
+        composed = compose(&composed, &map.entries);
+    }
+    return composed;
+}
+
+/// The pieces of source ids a normalised map sends into a range, and how far each is moved
+fn preimage_pieces(range: Range<i64>, map: &[Entry]) -> Vec<(Range<i64>, i64)> {
+    let mut pieces: Vec<(Range<i64>, i64)> = vec![];
+    for entry in map {
+        let dests = entry.destinations();
+        let start = cmp::max(range.start, dests.start);
+        let end = cmp::min(range.end, dests.end);
+        if start < end {
+            pieces.push((
+                (start - entry.offset())..(end - entry.offset()),
+                entry.offset(),
+            ));
+        }
+    }
+    // Unmapped ids map to themselves
+    let claimed: Vec<Range<i64>> = map.iter().map(Entry::sources).collect();
+    for piece in subtract(range, &claimed) {
+        pieces.push((piece, 0));
+    }
+    return pieces;
+}
+
+/// Every range of ids at the start of the chain that ends up in one of the ranges
+fn invert_ranges(ranges: &[Range<i64>], chain: &[&AlmanacMap]) -> Vec<Range<i64>> {
+    let mut ranges = ranges.to_vec();
+    for map in chain.iter().rev() {
+        let map = normalise(&map.entries);
+        let mut sources: Vec<Range<i64>> = vec![];
+        for range in ranges {
+            sources.extend(
+                preimage_pieces(range, &map)
+                    .into_iter()
+                    .map(|(piece, _)| piece),
+            );
+        }
+        ranges = merge_ranges(sources);
+    }
+    return ranges;
+}
+
+/// The lowest location a range of seeds reaches through a normalised map, and the seed that
+/// gets there
+fn lowest_in_range(range: Range<i64>, map: &[Entry]) -> Option<(i64, i64)> {
+    let mut lowest: Option<(i64, i64)> = None;
+    for (piece, offset) in split_through(range, map) {
+        // Pieces move as a block, so their start is always the lowest
+        let location = piece.start + offset;
+        if lowest.is_none_or(|(_, low)| location < low) {
+            lowest = Some((piece.start, location));
+        }
+    }
+    return lowest;
+}
+
+/// Sort ranges and merge any that overlap or touch
+fn merge_ranges(mut ranges: Vec<Range<i64>>) -> Vec<Range<i64>> {
+    ranges.retain(|range| !range.is_empty());
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<i64>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = cmp::max(last.end, range.end),
+            _ => merged.push(range),
+        }
+    }
+    return merged;
+}
+
+/// Find the smallest location any of the seeds reach by working backwards from the locations.
+///
+/// The composed map moves whole intervals of locations by the same amount, so walk those in
+/// ascending order and stop at the first whose preimage hits a seed.
+fn lowest_by_inverse(seeds: &[Range<i64>], chain: &[&AlmanacMap]) -> Option<i64> {
+    let composed = compose_chain(chain);
+    let mut bounds: Vec<i64> = vec![i64::MIN, i64::MAX];
+    for entry in &composed {
+        let sources = entry.sources();
+        let dests = entry.destinations();
+        bounds.extend([sources.start, sources.end, dests.start, dests.end]);
+    }
+    bounds.sort();
+    bounds.dedup();
+    for interval in bounds.windows(2) {
+        let mut lowest: Option<i64> = None;
+        for (piece, offset) in preimage_pieces(interval[0]..interval[1], &composed) {
+            for seed in seeds {
+                let start = cmp::max(piece.start, seed.start);
+                if start < cmp::min(piece.end, seed.end) {
+                    let location = start + offset;
+                    lowest = Some(lowest.map_or(location, |low| cmp::min(low, location)));
+                }
+            }
+        }
+        if lowest.is_some() {
+            return lowest;
+        }
+    }
+    return None;
+}
+
+fn print_map(map: &[Entry]) {
+    for entry in map {
+        entry.print();
+    }
+}
+
+/// Split ranges into `parts` pieces with about the same number of ids in each
+fn split_work(ranges: &[Range<i64>], parts: usize) -> Vec<Vec<Range<i64>>> {
+    let total: i64 = ranges.iter().map(|range| range.end - range.start).sum();
+    let per_part = cmp::max(1, (total + parts as i64 - 1) / parts as i64);
+    let mut work: Vec<Vec<Range<i64>>> = vec![vec![]];
+    let mut room = per_part;
+    for range in ranges {
+        let mut start = range.start;
+        while start < range.end {
+            if room == 0 {
+                work.push(vec![]);
+                room = per_part;
+            }
+            let end = cmp::min(range.end, start + room);
+            work.last_mut().expect("").push(start..end);
+            room -= end - start;
+            start = end;
+        }
+    }
+    return work;
+}
+
+/// Find the lowest location by looking up every single seed, spread across threads.
+///
+/// Slow, but shares none of the range logic, so it's a check on the fast path.
+fn brute_force_lowest(
+    seeds: &[Range<i64>],
+    chain: &[&AlmanacMap],
+    threads: usize,
+) -> Option<(i64, i64)> {
+    let total: u64 = seeds
+        .iter()
+        .map(|range| (range.end - range.start) as u64)
+        .sum();
+    let done = AtomicU64::new(0);
+    let work = split_work(seeds, cmp::max(1, threads));
+    println!("Brute forcing {} seeds on {} threads", total, work.len());
+    return thread::scope(|scope| {
+        let handles: Vec<_> = work
+            .iter()
+            .map(|ranges| {
+                let done = &done;
+                scope.spawn(move || {
+                    let mut lowest: Option<(i64, i64)> = None;
+                    for range in ranges {
+                        for seed in range.clone() {
+                            let location = generate_loc_linear(seed, chain);
+                            if lowest.is_none_or(|(_, low)| location < low) {
+                                lowest = Some((seed, location));
+                            }
+                            // Only touch the shared counter now and then
+                            if (seed - range.start) % 0x10000 == 0xffff {
+                                done.fetch_add(0x10000, Ordering::Relaxed);
+                            }
+                        }
+                        done.fetch_add(
+                            ((range.end - range.start) % 0x10000) as u64,
+                            Ordering::Relaxed,
+                        );
+                    }
+                    return lowest;
+                })
+            })
+            .collect();
+
+        // Poll often so small runs don't wait around, but only report once a second
+        let mut ticks = 0;
+        while handles.iter().any(|handle| !handle.is_finished()) {
+            thread::sleep(Duration::from_millis(50));
+            ticks += 1;
+            if ticks % 20 == 0 && total > 0 {
+                let so_far = done.load(Ordering::Relaxed);
+                eprint!(
+                    "\rBrute force: {:.1}%",
+                    100.0 * so_far as f64 / total as f64
+                );
+            }
+        }
+        if ticks >= 20 && total > 0 {
+            eprintln!();
+        }
+
+        let mut lowest: Option<(i64, i64)> = None;
+        for handle in handles {
+            let found = handle.join().expect("Brute force thread panicked");
+            if let Some((seed, location)) = found {
+                if lowest.is_none_or(|(_, low)| location < low) {
+                    lowest = Some((seed, location));
+                }
+            }
+        }
+        lowest
+    });
+}
+
+/// Tiny xorshift generator, so benchmark almanacs are the same from run to run
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+    fn below(&mut self, bound: i64) -> i64 {
+        return (self.next() % bound as u64) as i64;
+    }
+}
+
+/// Time point lookups through a chain of large random maps, scanning against binary search
+fn bench(entries: usize, lookups: usize) {
+    const SPAN: i64 = 1 << 40;
+    let mut rng = XorShift(0x2023_1205);
+    let mut maps: Vec<AlmanacMap> = vec![];
+    for stage in 0..7 {
+        let mut map = AlmanacMap {
+            from: format!("stage{}", stage),
+            to: format!("stage{}", stage + 1),
+            entries: vec![],
+            lines: vec![],
+            sorted: vec![],
+        };
+        for _ in 0..entries {
+            map.entries.push(Entry {
+                dest: rng.below(SPAN),
+                source: rng.below(SPAN),
+                length: 1 + rng.below(SPAN / entries as i64),
+            });
+        }
+        map.sorted = sort_entries(&map.entries);
+        maps.push(map);
+    }
+    let chain: Vec<&AlmanacMap> = maps.iter().collect();
+    let ids: Vec<i64> = (0..lookups).map(|_| rng.below(SPAN)).collect();
+    println!("{} entries per map, {} lookups", entries, lookups);
+
+    let start = Instant::now();
+    let linear: Vec<i64> = ids
+        .iter()
+        .map(|id| generate_loc_linear(*id, &chain))
         .collect();
-    println!(
-        "Min is {}",
-        seeds.iter().min().unwrap()
-    );
+    println!("Linear: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let sorted: Vec<i64> = ids.iter().map(|id| generate_loc(*id, &chain)).collect();
+    println!("Sorted: {:?}", start.elapsed());
+    assert!(linear == sorted, "Lookups disagree");
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // bench [entries] [lookups] compares lookups on synthetic almanacs, so needs no data
+    if args.first().map(String::as_str) == Some("bench") {
+        let entries = args.get(1).map_or(1000, |arg| parse_or_panic::<usize>(arg));
+        let lookups = args
+            .get(2)
+            .map_or(100_000, |arg| parse_or_panic::<usize>(arg));
+        bench(entries, lookups);
+        return;
+    }
+    // Read our calibration file
+    let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
+    let almanac = Almanac::read_from_string(&file);
+    let problems = almanac.validate();
+    // validate just reports what's wrong with the almanac
+    if args.first().map(String::as_str) == Some("validate") {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!("{} problems found", problems.len());
+        return;
+    }
+    for problem in &problems {
+        eprintln!("Warning: {}", problem);
+    }
+    if !almanac.malformed.is_empty() {
+        panic!("data.txt has malformed lines");
+    }
+
+    // query <from> <to> <id>... looks ids up through whatever maps connect the two categories
+    if args.first().map(String::as_str) == Some("query") {
+        if args.len() < 3 {
+            panic!("Usage: query <from> <to> <id>...");
+        }
+        let chain = almanac.chain_or_panic(&args[1], &args[2]);
+        for id in args[3..].iter().map(|id| parse_or_panic::<i64>(id)) {
+            println!(
+                "{} {} -> {} {}",
+                args[1],
+                id,
+                args[2],
+                generate_loc(id, &chain)
+            );
+        }
+        return;
+    }
+
+    // preimage <from> <to> <id>... finds every id that ends up at each of the ids
+    if args.first().map(String::as_str) == Some("preimage") {
+        if args.len() < 3 {
+            panic!("Usage: preimage <from> <to> <id>...");
+        }
+        let chain = almanac.chain_or_panic(&args[1], &args[2]);
+        for id in args[3..].iter().map(|id| parse_or_panic::<i64>(id)) {
+            let sources = find_sources_through(id, &chain);
+            println!("{} {} <- {} {:?}", args[2], id, args[1], sources);
+        }
+        return;
+    }
+    // preimage-range <from> <to> <start> <length> finds the ranges of ids that end up in the range
+    if args.first().map(String::as_str) == Some("preimage-range") {
+        if args.len() != 5 {
+            panic!("Usage: preimage-range <from> <to> <start> <length>");
+        }
+        let chain = almanac.chain_or_panic(&args[1], &args[2]);
+        let start = parse_or_panic::<i64>(&args[3]);
+        let range = start..(start + parse_or_panic::<i64>(&args[4]));
+        for source in invert_ranges(std::slice::from_ref(&range), &chain) {
+            println!("{} {:?} <- {} {:?}", args[2], range, args[1], source);
+        }
+        return;
+    }
+
+    let mut individual = false;
+    let mut verbose = false;
+    let mut brute_force = false;
+    let mut composed = false;
+    let mut inverse = false;
+    let mut threads = thread::available_parallelism().map_or(1, |count| count.get());
+    for arg in &args {
+        match arg.as_str() {
+            "--individual" => individual = true,
+            "--ranges" => individual = false,
+            "--verbose" => verbose = true,
+            "--brute-force" => brute_force = true,
+            "--composed" => composed = true,
+            "--inverse" => inverse = true,
+            _ => match arg.strip_prefix("--threads=") {
+                Some(count) => threads = parse_or_panic::<usize>(count),
+                None => panic!(
+                    "Unknown argument '{}', expected --individual, --ranges, --verbose, --brute-force, --composed, --inverse or --threads=",
+                    arg
+                ),
+            },
+        }
+    }
+
+    let seeds: Vec<Range<i64>> = if individual {
+        almanac.seeds.iter().map(|seed| *seed..(seed + 1)).collect()
+    } else {
+        // Seeds come in (start, length) pairs
+        if !almanac.seeds.len().is_multiple_of(2) {
+            panic!("Seed ranges need a length for every start");
+        }
+        almanac
+            .seeds
+            .chunks(2)
+            .map(|pair| {
+                if pair[1] < 0 {
+                    panic!("Seed range starting at {} has a negative length", pair[0]);
+                }
+                return pair[0]..(pair[0] + pair[1]);
+            })
+            .collect()
+    };
+
+    let chain = almanac.chain_or_panic("seed", "location");
+    let seed_to_locs = compose_chain(&chain);
+    if composed {
+        println!("seed-to-location map:");
+        print_map(&seed_to_locs);
+    }
+
+    // The lowest location, and the seed that got there
+    let mut lowest: Option<(i64, i64)> = None;
+    if individual {
+        for seed in &almanac.seeds {
+            let location = generate_loc(*seed, &chain);
+            if verbose {
+                println!("Seed {} -> location {}", seed, location);
+            }
+            if lowest.is_none_or(|(_, low)| location < low) {
+                lowest = Some((*seed, location));
+            }
+        }
+    } else {
+        for range in &seeds {
+            if verbose {
+                let locations =
+                    merge_ranges(map_ranges(std::slice::from_ref(range), &seed_to_locs));
+                println!("Seeds {:?} -> locations {:?}", range, locations);
+            }
+            if let Some((seed, location)) = lowest_in_range(range.clone(), &seed_to_locs) {
+                if lowest.is_none_or(|(_, low)| location < low) {
+                    lowest = Some((seed, location));
+                }
+            }
+        }
+    }
+    match lowest {
+        Some((seed, location)) => println!("Min is {}, from seed {}", location, seed),
+        None => println!("No seeds"),
+    }
+    if brute_force {
+        let slow = brute_force_lowest(&seeds, &chain, threads);
+        match (slow, lowest) {
+            (Some((_, slow)), Some((_, fast))) if slow == fast => {
+                println!("Brute force agrees, min is {}", slow)
+            }
+            (None, None) => println!("Brute force agrees, no seeds"),
+            _ => panic!(
+                "Brute force found {:?} but the fast path found {:?}",
+                slow, lowest
+            ),
+        }
+    }
+    if inverse {
+        match lowest_by_inverse(&seeds, &chain) {
+            Some(location) => println!("Working backwards, min is {}", location),
+            None => println!("Working backwards, no location has a seed"),
+        }
+    }
 }
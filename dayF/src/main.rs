@@ -24,6 +24,9 @@
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+use num_bigint::BigUint;
+use std::cmp;
+use std::env;
 use std::fs;
 
 fn parse_or_panic<T: std::str::FromStr>(string: &str) -> T {
@@ -33,42 +36,525 @@ fn parse_or_panic<T: std::str::FromStr>(string: &str) -> T {
     }
 }
 
+/// The numbers after the label on a line, like "Time:      7  15   30"
+fn numbers_after_colon(line: &str) -> std::str::SplitWhitespace<'_> {
+    return line.split(':').nth(1).expect("").split_whitespace();
+}
+
+/// Count the hold times that beat the record without trying them all.
+///
+/// Holding for h travels h * (time - h), so we win whenever h^2 - time * h + dist < 0, which is
+/// between the roots (time +- sqrt(time^2 - 4 * dist)) / 2. The integer square root only gets us
+/// close, so nudge the lowest hold until it's the first that wins. The highest is its mirror image.
+/// Returns None if time^2 doesn't fit in a u128.
+fn ways_to_win_u128(time: u128, dist: u128) -> Option<u128> {
+    let square = time.checked_mul(time)?;
+    let four_dist = dist.checked_mul(4)?;
+    // At best we tie the record
+    if square <= four_dist {
+        return Some(0);
+    }
+    let disc = square - four_dist;
+    // Can't overflow, since it's at most time^2 / 4
+    let wins = |hold: u128| hold * (time - hold) > dist;
+    let mut low = (time - disc.isqrt()) / 2;
+    while low > 0 && wins(low - 1) {
+        low -= 1;
+    }
+    while low <= time / 2 && !wins(low) {
+        low += 1;
+    }
+    if low > time / 2 {
+        return Some(0);
+    }
+    let high = time - low;
+    return Some(high - low + 1);
+}
+
+/// ways_to_win_u128, for races of any size
+fn ways_to_win_big(time: &BigUint, dist: &BigUint) -> BigUint {
+    let one = BigUint::from(1u32);
+    let square = time * time;
+    let four_dist = dist * 4u32;
+    // At best we tie the record
+    if square <= four_dist {
+        return BigUint::ZERO;
+    }
+    let disc = square - four_dist;
+    let wins = |hold: &BigUint| hold * (time - hold) > *dist;
+    let half = time / 2u32;
+    let mut low = (time - disc.sqrt()) / 2u32;
+    while low > BigUint::ZERO && wins(&(&low - &one)) {
+        low -= &one;
+    }
+    while low <= half && !wins(&low) {
+        low += &one;
+    }
+    if low > half {
+        return BigUint::ZERO;
+    }
+    let high = time - &low;
+    return high - low + one;
+}
+
 fn ways_to_win_for(time: i64, dist: i64) -> i64 {
-    let mut ways = 0;
-    for i in 0..time {
-        let travels = i * (time - i);
-        //println!("{} travels {}", i, travels);
-        if travels > dist {
-            ways += 1;
+    if time < 0 || dist < 0 {
+        panic!(
+            "Race of {}ms with record {}mm can't be negative",
+            time, dist
+        );
+    }
+    // i64^2 always fits in a u128, and we can't win more ways than there are milliseconds
+    let ways = ways_to_win_u128(time as u128, dist as u128).expect("");
+    return ways as i64;
+}
+
+/// Solve a race given as strings of digits, using big integers if it doesn't fit in a u128
+fn ways_to_win_digits(time: &str, dist: &str) -> BigUint {
+    if let (Ok(time), Ok(dist)) = (time.parse::<u128>(), dist.parse::<u128>()) {
+        if let Some(ways) = ways_to_win_u128(time, dist) {
+            return BigUint::from(ways);
         }
     }
-    return ways;
+    eprintln!("Race is too big for a u128, using big integers");
+    let time: BigUint = parse_or_panic(time);
+    let dist: BigUint = parse_or_panic(dist);
+    return ways_to_win_big(&time, &dist);
+}
+
+/// How to play a race, and how well it goes
+struct RaceReport {
+    time: i64,
+    dist: i64,
+    // The shortest and longest holds that beat the record, if any do
+    winning: Option<(i64, i64)>,
+    best_hold: i64,
+    best_dist: i128,
+}
+
+impl RaceReport {
+    fn for_race(model: &dyn BoatModel, time: i64, dist: i64) -> RaceReport {
+        let best_hold = model.best_hold(time);
+        return RaceReport {
+            time,
+            dist,
+            winning: model.winning_holds(time, dist),
+            best_hold,
+            best_dist: model.distance(best_hold, time),
+        };
+    }
+    fn print(&self) {
+        println!("Race of {}ms, record {}mm", self.time, self.dist);
+        match self.winning {
+            Some((low, high)) => println!(
+                "  Hold {}ms to {}ms to win, {} ways",
+                low,
+                high,
+                high - low + 1
+            ),
+            None => println!("  No way to win"),
+        }
+        println!(
+            "  Best is holding {}ms to travel {}mm, {}mm over the record",
+            self.best_hold,
+            self.best_dist,
+            self.best_dist - self.dist as i128
+        );
+    }
+    /// Plot distance against hold time, with the record marked by a line of -
+    fn plot(&self, model: &dyn BoatModel) {
+        const COLUMNS: i64 = 60;
+        const ROWS: i128 = 16;
+        if self.best_dist == 0 {
+            println!("Nothing to plot, no hold gets the boat moving");
+            return;
+        }
+        let columns = cmp::min(COLUMNS, self.time + 1);
+        let holds: Vec<i64> = (0..columns)
+            .map(|col| {
+                if columns == 1 {
+                    return 0;
+                }
+                (col as i128 * self.time as i128 / (columns - 1) as i128) as i64
+            })
+            .collect();
+        let top = cmp::max(self.best_dist, self.dist as i128 + 1);
+        // The row whose band the record falls in
+        let record_row = ROWS - 1 - (self.dist as i128 * ROWS / (top + 1));
+        for row in 0..ROWS {
+            let level = top * (ROWS - row) / ROWS;
+            let mut line = String::new();
+            for hold in &holds {
+                // Every row sits above the baseline, so going nowhere never reaches one
+                let reached = model.distance(*hold, self.time) >= cmp::max(level, 1);
+                line.push(match (reached, row == record_row) {
+                    (true, true) => '+',
+                    (true, false) => '#',
+                    (false, true) => '-',
+                    (false, false) => ' ',
+                });
+            }
+            let label = if row == record_row {
+                format!("record {}", self.dist)
+            } else if row == 0 {
+                level.to_string()
+            } else {
+                String::new()
+            };
+            println!("{:>16} |{}", label, line);
+        }
+        println!("{:>16} +{}", 0, "-".repeat(holds.len()));
+        // Keep a gap between the labels, even when there are only a few columns
+        let end = format!("{}ms", self.time);
+        println!(
+            "{:>16}  0ms{:>width$}",
+            "",
+            end,
+            width = cmp::max(holds.len().saturating_sub(3), end.len() + 1)
+        );
+    }
+}
+
+/// How far holding the button for `hold` gets us
+fn travels(hold: i64, time: i64) -> i128 {
+    return hold as i128 * (time - hold) as i128;
+}
+
+/// How far a boat goes for a given hold time.
+///
+/// Models only have to say how far a hold gets us. Counting the winning holds falls back on a
+/// search, which assumes the distance rises to a peak and then falls again, like all the boats
+/// here do. Models with a closed form can override it.
+trait BoatModel {
+    fn distance(&self, hold: i64, time: i64) -> i128;
+
+    /// The hold that goes furthest
+    fn best_hold(&self, time: i64) -> i64 {
+        // Find the first hold that goes no further than the one after it
+        let (mut low, mut high) = (0, time);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.distance(mid, time) >= self.distance(mid + 1, time) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        return low;
+    }
+
+    /// The shortest and longest holds that beat the record, if any do
+    fn winning_holds(&self, time: i64, dist: i64) -> Option<(i64, i64)> {
+        let peak = self.best_hold(time);
+        if self.distance(peak, time) <= dist as i128 {
+            return None;
+        }
+        let wins = |hold: i64| self.distance(hold, time) > dist as i128;
+        // Distance rises up to the peak, so find the first hold that wins before it
+        let (mut low, mut high) = (0, peak);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if wins(mid) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        let first = low;
+        // And falls after, so find the last hold that wins after it
+        let (mut low, mut high) = (peak, time);
+        while low < high {
+            let mid = high - (high - low) / 2;
+            if wins(mid) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        return Some((first, low));
+    }
+
+    fn ways_to_win(&self, time: i64, dist: i64) -> i64 {
+        match self.winning_holds(time, dist) {
+            Some((low, high)) => high - low + 1,
+            None => 0,
+        }
+    }
+}
+
+/// Every ms held adds `rate` mm/ms of speed. A rate of 1 is the boat from the puzzle.
+struct Linear {
+    rate: i64,
+}
+
+impl BoatModel for Linear {
+    fn distance(&self, hold: i64, time: i64) -> i128 {
+        return self.rate as i128 * travels(hold, time);
+    }
+    fn best_hold(&self, time: i64) -> i64 {
+        return time / 2;
+    }
+    fn winning_holds(&self, time: i64, dist: i64) -> Option<(i64, i64)> {
+        // rate * x > dist exactly when x > dist / rate, rounding down
+        let ways = ways_to_win_for(time, dist / self.rate);
+        if ways == 0 {
+            return None;
+        }
+        // The winning holds are centred on time / 2, so the count tells us where they start
+        let low = (time + 1 - ways) / 2;
+        return Some((low, time - low));
+    }
+}
+
+/// Like Linear, but the boat can't go faster than `cap` mm/ms
+struct Capped {
+    rate: i64,
+    cap: i64,
+}
+
+impl BoatModel for Capped {
+    fn distance(&self, hold: i64, time: i64) -> i128 {
+        let speed = cmp::min(self.rate as i128 * hold as i128, self.cap as i128);
+        return speed * (time - hold) as i128;
+    }
+}
+
+/// Like Linear, but the boat loses `decay` mm/ms of speed every ms once it's let go
+struct Decaying {
+    rate: i64,
+    decay: i64,
+}
+
+impl BoatModel for Decaying {
+    fn distance(&self, hold: i64, time: i64) -> i128 {
+        let speed = self.rate as i128 * hold as i128;
+        let left = (time - hold) as i128;
+        let decay = self.decay as i128;
+        // It moves speed, speed - decay, speed - 2 * decay... until it stops or time runs out
+        let moving = cmp::min(left, (speed + decay - 1) / decay);
+        return moving * speed - decay * moving * (moving - 1) / 2;
+    }
+}
+
+/// Read a model like "linear", "linear:2", "capped:1:30" or "decay:2:1"
+fn model_from_string(string: &str) -> Box<dyn BoatModel> {
+    let parts: Vec<&str> = string.split(':').collect();
+    let number = |idx: usize, default: i64| -> i64 {
+        let value = parts
+            .get(idx)
+            .map_or(default, |part| parse_or_panic::<i64>(part));
+        if value <= 0 {
+            panic!("Model '{}' needs positive numbers", string);
+        }
+        return value;
+    };
+    return match parts[0] {
+        "linear" => Box::new(Linear { rate: number(1, 1) }),
+        "capped" => Box::new(Capped {
+            rate: number(1, 1),
+            cap: number(2, i64::MAX),
+        }),
+        "decay" => Box::new(Decaying {
+            rate: number(1, 1),
+            decay: number(2, 1),
+        }),
+        _ => panic!(
+            "Unknown model '{}', expected linear[:rate], capped:rate:cap or decay:rate:decay",
+            string
+        ),
+    };
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // Either every column is its own race, or the kerning is bad and it's all one big race
+    let mut multi = false;
+    let mut report = false;
+    let mut plot = false;
+    // None is the boat from the puzzle, which we can solve at any size
+    let mut model: Option<Box<dyn BoatModel>> = None;
+    for arg in &args {
+        match arg.as_str() {
+            "--multi" => multi = true,
+            "--kerned" => multi = false,
+            "--report" => report = true,
+            "--plot" => plot = true,
+            _ => match arg.strip_prefix("--model=") {
+                Some(name) => model = Some(model_from_string(name)),
+                None => panic!(
+                    "Unknown argument '{}', expected --multi, --kerned, --report, --plot or --model=",
+                    arg
+                ),
+            },
+        }
+    }
+    let puzzle_boat = model.is_none();
+    let model: &dyn BoatModel = match &model {
+        Some(model) => model.as_ref(),
+        None => &Linear { rate: 1 },
+    };
+    let show = |time: i64, dist: i64| {
+        let race = RaceReport::for_race(model, time, dist);
+        if report {
+            race.print();
+        }
+        if plot {
+            race.plot(model);
+        }
+    };
+
     // Read our calibration file and split it by line
     let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
     let data: Vec<&str> = file.split('\n').collect();
-    let times: i64 = parse_or_panic(
-        &data[0]
-            .split(':')
-            .skip(1)
-            .next()
-            .expect("")
-            .split_whitespace()
-            .collect::<String>(),
-    );
-    let dist: i64 = parse_or_panic(
-        &data[1]
-            .split(':')
-            .skip(1)
-            .next()
-            .expect("")
-            .split_whitespace()
-            .collect::<String>(),
-    );
-    println!("{}", ways_to_win_for(7, 9));
-    let mut product = 1;
-    let wins = ways_to_win_for(times, dist);
-    println!("Wins are {}", wins);
+    if multi {
+        let times: Vec<i64> = numbers_after_colon(data[0])
+            .map(parse_or_panic::<i64>)
+            .collect();
+        let dists: Vec<i64> = numbers_after_colon(data[1])
+            .map(parse_or_panic::<i64>)
+            .collect();
+        if times.len() != dists.len() {
+            panic!("{} times but {} distances", times.len(), dists.len());
+        }
+        let mut product: i64 = 1;
+        for (time, dist) in times.into_iter().zip(dists) {
+            show(time, dist);
+            let wins = model.ways_to_win(time, dist);
+            println!(
+                "Race of {}ms, record {}mm: {} ways to win",
+                time, dist, wins
+            );
+            product = match i64::checked_mul(product, wins) {
+                Some(product) => product,
+                None => panic!("Product of ways to win overflowed an i64"),
+            };
+        }
+        println!("Product is {}", product);
+    } else {
+        let time: String = numbers_after_colon(data[0]).collect();
+        let dist: String = numbers_after_colon(data[1]).collect();
+        if report || plot {
+            match (time.parse::<i64>(), dist.parse::<i64>()) {
+                (Ok(time), Ok(dist)) => show(time, dist),
+                _ => eprintln!("Race is too big for an i64, so can't report on it"),
+            }
+        }
+        if puzzle_boat {
+            let wins = ways_to_win_digits(&time, &dist);
+            println!("Wins are {}", wins);
+        } else {
+            let wins = model.ways_to_win(parse_or_panic(&time), parse_or_panic(&dist));
+            println!("Wins are {}", wins);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Count the hold times that beat the record, trying every one
+    fn ways_to_win_by_loop(time: i64, dist: i64) -> i64 {
+        let mut ways = 0;
+        for i in 0..time {
+            // Widened, since the distance can overflow long before the time does
+            let travels = i as i128 * (time - i) as i128;
+            //println!("{} travels {}", i, travels);
+            if travels > dist as i128 {
+                ways += 1;
+            }
+        }
+        return ways;
+    }
+
+    /// Tiny xorshift generator, so checks are the same from run to run
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return self.0;
+        }
+        fn below(&mut self, bound: i64) -> i64 {
+            return (self.next() % bound as u64) as i64;
+        }
+    }
+
+    #[test]
+    fn closed_form_matches_loop() {
+        let mut rng = XorShift(0x2023_1206);
+        for _ in 0..100_000 {
+            let time = rng.below(2000);
+            // Mostly records near the best possible, where the boundaries are trickiest
+            let best = (time / 2) * (time - time / 2);
+            let dist = match rng.below(3) {
+                0 => rng.below(best + 2),
+                1 => cmp::max(0, best - rng.below(5)),
+                _ => rng.below(best * 2 + 1),
+            };
+            let found = ways_to_win_for(time, dist);
+            assert_eq!(
+                found,
+                ways_to_win_by_loop(time, dist),
+                "time {}, dist {}",
+                time,
+                dist
+            );
+            let big = ways_to_win_big(&BigUint::from(time as u64), &BigUint::from(dist as u64));
+            assert_eq!(
+                big,
+                BigUint::from(found as u64),
+                "time {}, dist {}",
+                time,
+                dist
+            );
+        }
+    }
+
+    #[test]
+    fn u128_matches_big_integers() {
+        // Races far too long for the loop, where all that's left is to compare the closed forms
+        let mut rng = XorShift(0x2023_1206);
+        for _ in 0..100_000 {
+            let time = (rng.next() >> 2) as u128;
+            let best = (time / 2) * (time - time / 2);
+            let dist = best - (rng.next() as u128 % best);
+            let found = ways_to_win_u128(time, dist).expect("");
+            let big = ways_to_win_big(&BigUint::from(time), &BigUint::from(dist));
+            assert_eq!(big, BigUint::from(found), "time {}, dist {}", time, dist);
+        }
+    }
+
+    #[test]
+    fn models_match_trying_every_hold() {
+        let mut rng = XorShift(0x2023_1207);
+        let models: Vec<Box<dyn BoatModel>> = vec![
+            Box::new(Linear { rate: 3 }),
+            Box::new(Capped { rate: 2, cap: 50 }),
+            Box::new(Decaying { rate: 3, decay: 2 }),
+        ];
+        for model in &models {
+            for _ in 0..1000 {
+                let time = rng.below(400);
+                let best = (0..=time)
+                    .map(|hold| model.distance(hold, time))
+                    .max()
+                    .expect("");
+                let dist = rng.below(best as i64 + 2);
+                let expected = (0..=time)
+                    .filter(|hold| model.distance(*hold, time) > dist as i128)
+                    .count() as i64;
+                assert_eq!(
+                    model.ways_to_win(time, dist),
+                    expected,
+                    "time {}, dist {}",
+                    time,
+                    dist
+                );
+            }
+        }
+    }
 }
@@ -1,5 +1,3 @@
-use std::cmp;
-use std::collections::HashMap;
 /**
  * BSD 2-Clause License
  *
@@ -26,118 +24,585 @@ use std::collections::HashMap;
  * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
+use std::cmp;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::env;
 use std::fs;
 
-// Thanks, https://users.rust-lang.org/t/how-to-sort-enum-variants/52291/2
-#[derive(PartialOrd, Ord, PartialEq, Eq, Debug)]
-enum Hand {
-    HighCard(i32),
-    OnePair(i32),
-    TwoPair(i32),
-    ThreeOfAKind(i32),
-    FullHouse(i32),
-    FourOfAKind(i32),
-    FiveOfAKind(i32),
+/// The type of a hand is how many of each card it has, biggest group first.
+///
+/// Comparing those groups in order ranks hands the usual way, for any size of hand: five of a kind
+/// [5] beats four of a kind [4, 1] beats a full house [3, 2], and so on down to high card.
+#[derive(PartialOrd, Ord, PartialEq, Eq, Clone, Debug)]
+struct HandType {
+    groups: Vec<i32>,
+}
+
+impl HandType {
+    fn from_counts(mut groups: Vec<i32>) -> HandType {
+        groups.retain(|count| *count > 0);
+        groups.sort_by(|a, b| b.cmp(a));
+        return HandType { groups };
+    }
+    fn name(&self) -> String {
+        let rest_single = |from: usize| self.groups[from..].iter().all(|count| *count == 1);
+        return match self.groups.as_slice() {
+            [] => "empty hand".to_string(),
+            [1, ..] => "high card".to_string(),
+            [2, 2, ..] if rest_single(2) => "two pair".to_string(),
+            [3, 2] => "full house".to_string(),
+            [n, ..] if rest_single(1) => match n {
+                2 => "one pair".to_string(),
+                3 => "three of a kind".to_string(),
+                4 => "four of a kind".to_string(),
+                5 => "five of a kind".to_string(),
+                _ => format!("{} of a kind", n),
+            },
+            groups => groups
+                .iter()
+                .map(i32::to_string)
+                .collect::<Vec<String>>()
+                .join("+"),
+        };
+    }
+}
+
+/// Which cards there are, how strong each is, and how big a hand is
+struct Rules {
+    // Weakest first
+    ranks: Vec<char>,
+    // The card that stands in for whatever makes the best hand, if any does
+    joker: Option<char>,
+    hand_size: usize,
+}
+
+impl Rules {
+    /// J is a Jack, between T and Q
+    fn standard() -> Rules {
+        return Rules {
+            ranks: "23456789TJQKA".chars().collect(),
+            joker: None,
+            hand_size: 5,
+        };
+    }
+    /// J is a Joker, the weakest card, but it stands in for whatever makes the best hand
+    fn joker() -> Rules {
+        return Rules {
+            ranks: "J23456789TQKA".chars().collect(),
+            joker: Some('J'),
+            hand_size: 5,
+        };
+    }
+    /// Read rules from lines like "ranks = 23456789TJQKA", "joker = J" and "hand_size = 5".
+    /// Anything not given is the same as the standard rules.
+    fn from_string(string: &str) -> Rules {
+        let mut rules = Rules::standard();
+        for line in string.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => panic!("Couldn't parse rule '{}'", line),
+            };
+            match key {
+                "ranks" => rules.ranks = value.chars().collect(),
+                "joker" => rules.joker = value.chars().next(),
+                "hand_size" => match value.parse::<usize>() {
+                    Ok(size) => rules.hand_size = size,
+                    Err(_) => panic!("Couldn't parse hand size '{}'", value),
+                },
+                _ => panic!("Unknown rule '{}'", key),
+            }
+        }
+        if rules.ranks.is_empty() {
+            panic!("Ranks can't be empty");
+        }
+        for (idx, rank) in rules.ranks.iter().enumerate() {
+            if rules.ranks[..idx].contains(rank) {
+                panic!("Rank '{}' is listed more than once", rank);
+            }
+        }
+        if let Some(joker) = rules.joker {
+            if !rules.ranks.contains(&joker) {
+                panic!("Joker '{}' isn't one of the ranks", joker);
+            }
+            // Otherwise there's nothing for it to stand in for
+            if rules.ranks.len() == 1 {
+                panic!("Joker '{}' can't be the only rank", joker);
+            }
+        }
+        return rules;
+    }
+}
+
+#[derive(PartialOrd, Ord, PartialEq, Eq, Clone, Copy, Debug)]
+struct Card {
+    // How strong the card is under the rules it was read with
+    value: i32,
+    label: char,
 }
 
-fn promote(hand: Hand, jokers: i32) -> Hand {
+impl Card {
+    fn from_char(c: char, rules: &Rules) -> Card {
+        let value = match rules.ranks.iter().position(|rank| *rank == c) {
+            Some(idx) => idx as i32,
+            None => panic!("'{}' isn't a card", c),
+        };
+        return Card { value, label: c };
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+struct Hand {
+    kind: HandType,
+    // The type before any jokers stand in for other cards
+    unpromoted: HandType,
+    cards: Vec<Card>,
+}
+
+impl Hand {
+    fn label(&self) -> String {
+        return self.cards.iter().map(|card| card.label).collect();
+    }
+}
+
+impl Ord for Hand {
+    /// Stronger types win. Between hands of the same type, the first card that differs decides.
+    fn cmp(&self, other: &Hand) -> cmp::Ordering {
+        return self
+            .kind
+            .cmp(&other.kind)
+            .then_with(|| self.cards.cmp(&other.cards));
+    }
+}
+
+impl PartialOrd for Hand {
+    fn partial_cmp(&self, other: &Hand) -> Option<cmp::Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+/// The best a hand can be once its jokers stand in for other cards.
+///
+/// `hand` counts the jokers as a group of their own. Whatever the hand, the jokers do best copying
+/// the biggest other group, since that makes the biggest group there can be.
+fn promote(hand: HandType, jokers: i32) -> HandType {
     if jokers == 0 {
         return hand;
     }
-    match hand {
-        // Lucky!
-        Hand::FiveOfAKind(_) => hand,
-        // Can't be zero, must be either 1 or 4
-        Hand::FourOfAKind(val) => Hand::FiveOfAKind(val),
-        // Can't be zero, must be 2 or 3
-        Hand::FullHouse(val) => Hand::FiveOfAKind(val),
-        Hand::ThreeOfAKind(val) => Hand::FourOfAKind(val),
-        Hand::TwoPair(val) => match jokers {
-            1 => Hand::FullHouse(val),
-            2 => Hand::FourOfAKind(val),
-            _ => panic!("How do you have {} jokers", jokers),
-        },
-        Hand::OnePair(val) => Hand::ThreeOfAKind(val),
-        Hand::HighCard(val) => Hand::OnePair(val),
-    }
-}
-
-fn parse_to_hand(string: &str) -> Hand {
-    let mut value: i32 = 0;
+    let mut groups = hand.groups;
+    match groups.iter().position(|count| *count == jokers) {
+        Some(idx) => groups.remove(idx),
+        None => panic!("How do you have {} jokers", jokers),
+    };
+    match groups.first_mut() {
+        Some(biggest) => *biggest += jokers,
+        // Nothing but jokers
+        None => groups.push(jokers),
+    }
+    return HandType::from_counts(groups);
+}
+
+fn parse_to_hand(string: &str, rules: &Rules) -> Hand {
+    let cards: Vec<Card> = string.chars().map(|c| Card::from_char(c, rules)).collect();
+    if cards.len() != rules.hand_size {
+        panic!(
+            "'{}' has {} cards, but hands have {}",
+            string,
+            cards.len(),
+            rules.hand_size
+        );
+    }
     let mut card_map = HashMap::<char, i32>::new();
-    for c in string.chars() {
-        value *= 14;
-        value += match c {
-            'A' => 13,
-            'K' => 12,
-            'Q' => 11,
-            'J' => 1,
-            'T' => 10,
-            '9' => 9,
-            '8' => 8,
-            '7' => 7,
-            '6' => 6,
-            '5' => 5,
-            '4' => 4,
-            '3' => 3,
-            '2' => 2,
-            _ => panic!(),
+    for card in &cards {
+        *card_map.entry(card.label).or_insert(0) += 1;
+    }
+    let jokers = match rules.joker {
+        Some(joker) => *card_map.get(&joker).unwrap_or(&0),
+        None => 0,
+    };
+    let kind = HandType::from_counts(card_map.into_values().collect());
+    return Hand {
+        kind: promote(kind.clone(), jokers),
+        unpromoted: kind,
+        cards,
+    };
+}
+
+/// Sort the hands and their bids from weakest to strongest under a set of rules
+fn rank_hands(data: &[&str], rules: &Rules) -> Vec<(Hand, i32)> {
+    let mut hands = Vec::<(Hand, i32)>::new();
+    for datum in data {
+        let bits: Vec<&str> = datum.split_whitespace().collect();
+        hands.push((
+            parse_to_hand(bits[0], rules),
+            bits[1].parse::<i32>().unwrap(),
+        ));
+    }
+    hands.sort();
+    return hands;
+}
+
+/// Rank the hands under a set of rules and add up their winnings
+fn total_winnings(data: &[&str], rules: &Rules) -> i32 {
+    let mut sum = 0;
+    for (i, (_, bid)) in rank_hands(data, rules).iter().enumerate() {
+        sum += (i + 1) as i32 * bid;
+    }
+    return sum;
+}
+
+/// Why a hand beat the one ranked just below it, if it came down to the cards
+fn tiebreak(hand: &Hand, below: &Hand) -> Option<String> {
+    if hand.kind != below.kind {
+        return None;
+    }
+    for (idx, (card, other)) in hand.cards.iter().zip(&below.cards).enumerate() {
+        if card != other {
+            return Some(format!(
+                "card {}: {} beats {}",
+                idx + 1,
+                card.label,
+                other.label
+            ));
+        }
+    }
+    return Some("tied".to_string());
+}
+
+fn print_table(data: &[&str], rules: &Rules) {
+    let hands = rank_hands(data, rules);
+    println!(
+        "{:>5} | {:<8} | {:<16} | {:<16} | {:>5} | {:>9} | Tiebreak",
+        "Rank", "Hand", "Type", "With jokers", "Bid", "Winnings"
+    );
+    for (i, (hand, bid)) in hands.iter().enumerate() {
+        let with_jokers = match rules.joker {
+            Some(_) => hand.kind.name(),
+            None => "-".to_string(),
+        };
+        let decided = match i {
+            0 => None,
+            _ => tiebreak(hand, &hands[i - 1].0),
         };
-        if card_map.contains_key(&c) {
-            card_map.insert(c, card_map.get(&c).unwrap() + 1);
+        println!(
+            "{:>5} | {:<8} | {:<16} | {:<16} | {:>5} | {:>9} | {}",
+            i + 1,
+            hand.label(),
+            hand.unpromoted.name(),
+            with_jokers,
+            bid,
+            (i + 1) as i32 * bid,
+            decided.unwrap_or_default()
+        );
+    }
+}
+
+/// Every way to pick `size` cards from the ranks, ignoring order
+fn multisets(ranks: &[char], size: usize) -> Vec<String> {
+    if size == 0 {
+        return vec![String::new()];
+    }
+    let mut hands: Vec<String> = vec![];
+    for (idx, rank) in ranks.iter().enumerate() {
+        // Only pick ranks from here on, so each multiset comes up once
+        for rest in multisets(&ranks[idx..], size - 1) {
+            hands.push(format!("{}{}", rank, rest));
+        }
+    }
+    return hands;
+}
+
+/// The best type a hand can be, trying every card in place of each joker
+fn best_by_substitution(hand: &str, rules: &Rules) -> HandType {
+    let joker = rules.joker.expect("Rules have no joker");
+    let stand_ins: Vec<char> = rules
+        .ranks
+        .iter()
+        .copied()
+        .filter(|c| *c != joker)
+        .collect();
+    let jokers = hand.chars().filter(|c| *c == joker).count();
+    let mut best: Option<HandType> = None;
+    // Count through every choice of stand-ins, like an odometer
+    let mut choice = vec![0; jokers];
+    loop {
+        let mut card_map = HashMap::<char, i32>::new();
+        let mut picks = choice.iter();
+        for c in hand.chars() {
+            let c = if c == joker {
+                stand_ins[*picks.next().expect("")]
+            } else {
+                c
+            };
+            *card_map.entry(c).or_insert(0) += 1;
+        }
+        let kind = HandType::from_counts(card_map.into_values().collect());
+        if best.as_ref().is_none_or(|best| kind > *best) {
+            best = Some(kind);
+        }
+
+        let mut digit = 0;
+        while digit < jokers && choice[digit] + 1 == stand_ins.len() {
+            choice[digit] = 0;
+            digit += 1;
+        }
+        if digit == jokers {
+            break;
+        }
+        choice[digit] += 1;
+    }
+    return best.expect("");
+}
+
+/// Check promote against trying every stand-in for the jokers, in every possible hand
+fn verify_promotion(rules: &Rules) {
+    let hands = multisets(&rules.ranks, rules.hand_size);
+    let mut disagreements = 0;
+    for hand in &hands {
+        let promoted = parse_to_hand(hand, rules).kind;
+        let best = best_by_substitution(hand, rules);
+        if promoted != best {
+            println!(
+                "{}: promote gives {:?} but the best is {:?}",
+                hand, promoted.groups, best.groups
+            );
+            disagreements += 1;
+        }
+    }
+    println!(
+        "Checked {} hands, {} disagreements",
+        hands.len(),
+        disagreements
+    );
+    if disagreements > 0 {
+        panic!("promote doesn't always find the best hand");
+    }
+}
+
+/// Say why one hand beats another: a better type, or the first card that differs
+fn explain(winner: &Hand, loser: &Hand) -> String {
+    if winner == loser {
+        return format!("{} ties {}", winner.label(), loser.label());
+    }
+    let decided = match tiebreak(winner, loser) {
+        Some(decided) => decided,
+        None => format!("{} beats {}", winner.kind.name(), loser.kind.name()),
+    };
+    return format!("{} over {}: {}", winner.label(), loser.label(), decided);
+}
+
+/// Classify some hands, order them strongest first, and explain each step of the order
+fn compare(strings: &[&str], rules: &Rules) {
+    let mut hands: Vec<Hand> = strings
+        .iter()
+        .map(|string| parse_to_hand(string, rules))
+        .collect();
+    for hand in &hands {
+        if hand.kind == hand.unpromoted {
+            println!("{}: {}", hand.label(), hand.kind.name());
         } else {
-            card_map.insert(c, 1);
+            println!(
+                "{}: {}, {} with jokers",
+                hand.label(),
+                hand.unpromoted.name(),
+                hand.kind.name()
+            );
         }
     }
-    if card_map.len() == 1 {
-        return promote(Hand::FiveOfAKind(value), *card_map.get(&'J').unwrap_or(&0));
+    hands.sort_by(|a, b| b.cmp(a));
+    let mut order = hands[0].label();
+    for pair in hands.windows(2) {
+        let between = if pair[0] == pair[1] { " = " } else { " > " };
+        order += between;
+        order += &pair[1].label();
     }
-    if card_map.len() == 2 {
-        for (k, v) in &card_map {
-            if *v == 4 || *v == 1 {
-                return promote(Hand::FourOfAKind(value), *card_map.get(&'J').unwrap_or(&0));
-            }
-            if *v == 3 || *v == 2 {
-                return promote(Hand::FullHouse(value), *card_map.get(&'J').unwrap_or(&0));
+    println!("Strongest first: {}", order);
+    for pair in hands.windows(2) {
+        println!("{}", explain(&pair[0], &pair[1]));
+    }
+}
+
+/// Tiny xorshift generator, so sampled hands are the same for the same seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+    fn below(&mut self, bound: usize) -> usize {
+        return (self.next() % bound as u64) as usize;
+    }
+}
+
+/// How hands drawn at random turn out, with every card equally likely in every position
+fn print_stats(rules: &Rules, queries: &[&str], samples: u64, seed: u64) {
+    let queries: Vec<Hand> = queries
+        .iter()
+        .map(|hand| parse_to_hand(hand, rules))
+        .collect();
+    // How many random hands each query beats, and ties with
+    let mut beats = vec![0u64; queries.len()];
+    let mut ties = vec![0u64; queries.len()];
+    let mut types = BTreeMap::<HandType, u64>::new();
+    let mut tally = |hand: Hand| {
+        for (idx, query) in queries.iter().enumerate() {
+            match query.cmp(&hand) {
+                cmp::Ordering::Greater => beats[idx] += 1,
+                cmp::Ordering::Equal => ties[idx] += 1,
+                cmp::Ordering::Less => (),
             }
         }
-    }
-    let mut pairs = 0;
-    for (k, v) in &card_map {
-        if *v == 3 {
-            return promote(Hand::ThreeOfAKind(value), *card_map.get(&'J').unwrap_or(&0));
+        *types.entry(hand.kind).or_insert(0) += 1;
+    };
+
+    let ranks = &rules.ranks;
+    let space = (ranks.len() as u64).checked_pow(rules.hand_size as u32);
+    let total = match space {
+        // Few enough hands to try them all
+        Some(space) if space <= samples => {
+            let mut choice = vec![0; rules.hand_size];
+            loop {
+                tally(parse_to_hand(
+                    &choice.iter().map(|idx| ranks[*idx]).collect::<String>(),
+                    rules,
+                ));
+                let mut digit = 0;
+                while digit < choice.len() && choice[digit] + 1 == ranks.len() {
+                    choice[digit] = 0;
+                    digit += 1;
+                }
+                if digit == choice.len() {
+                    break;
+                }
+                choice[digit] += 1;
+            }
+            println!("All {} hands:", space);
+            space
         }
-        if *v == 2 {
-            pairs += 1;
+        _ => {
+            let mut rng = XorShift(seed);
+            for _ in 0..samples {
+                let hand: String = (0..rules.hand_size)
+                    .map(|_| ranks[rng.below(ranks.len())])
+                    .collect();
+                tally(parse_to_hand(&hand, rules));
+            }
+            println!("{} random hands, seed {}:", samples, seed);
+            samples
         }
+    };
+
+    for (kind, count) in types.iter().rev() {
+        println!(
+            "  {:<16} {:>10} {:>8.4}%",
+            kind.name(),
+            count,
+            100.0 * *count as f64 / total as f64
+        );
     }
-    if pairs == 2 {
-        return promote(Hand::TwoPair(value), *card_map.get(&'J').unwrap_or(&0));
-    }
-    if pairs == 1 {
-        return promote(Hand::OnePair(value), *card_map.get(&'J').unwrap_or(&0));
+    for (idx, query) in queries.iter().enumerate() {
+        // Ties count as half a win
+        let percentile = 100.0 * (beats[idx] as f64 + ties[idx] as f64 / 2.0) / total as f64;
+        println!(
+            "  {} ({}) is at the {:.2} percentile",
+            query.label(),
+            query.kind.name(),
+            percentile
+        );
     }
-    return promote(Hand::HighCard(value), *card_map.get(&'J').unwrap_or(&0));
+}
+
+fn read_rules(path: &str) -> Rules {
+    return Rules::from_string(&fs::read_to_string(path).expect("Rules file not found or busy"));
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let rules_file = args.iter().find_map(|arg| arg.strip_prefix("--rules="));
+    // verify-jokers checks joker promotion by brute force, so needs no data
+    if args.first().map(String::as_str) == Some("verify-jokers") {
+        let rules = match rules_file {
+            Some(path) => read_rules(path),
+            None => Rules::joker(),
+        };
+        verify_promotion(&rules);
+        return;
+    }
+    // compare [--jokers] <hand> <hand>... explains which hands beat which
+    if args.first().map(String::as_str) == Some("compare") {
+        let rules = match rules_file {
+            Some(path) => read_rules(path),
+            None if args.iter().any(|arg| arg == "--jokers") => Rules::joker(),
+            None => Rules::standard(),
+        };
+        let hands: Vec<&str> = args[1..]
+            .iter()
+            .filter(|arg| !arg.starts_with("--"))
+            .map(String::as_str)
+            .collect();
+        if hands.len() < 2 {
+            panic!("Usage: compare [--jokers | --rules=<file>] <hand> <hand>...");
+        }
+        compare(&hands, &rules);
+        return;
+    }
+
+    // stats [--samples=N] [--seed=N] [hand...] shows how random hands turn out, and where the
+    // given hands fall among them
+    if args.first().map(String::as_str) == Some("stats") {
+        let mut samples: u64 = 1_000_000;
+        let mut seed: u64 = 0x2023_1207;
+        let mut hands: Vec<&str> = vec![];
+        for arg in &args[1..] {
+            if let Some(count) = arg.strip_prefix("--samples=") {
+                samples = count.parse::<u64>().expect("Sample count must be a number");
+                if samples == 0 {
+                    panic!("Sample count can't be zero");
+                }
+            } else if let Some(number) = arg.strip_prefix("--seed=") {
+                seed = number.parse::<u64>().expect("Seed must be a number");
+                if seed == 0 {
+                    panic!("Seed can't be zero");
+                }
+            } else if !arg.starts_with("--") {
+                hands.push(arg);
+            }
+        }
+        let rule_sets: Vec<(&str, Rules)> = match rules_file {
+            Some(path) => vec![("Rules file", read_rules(path))],
+            None => vec![("Standard", Rules::standard()), ("Jokers", Rules::joker())],
+        };
+        for (name, rules) in &rule_sets {
+            println!("{}", name);
+            print_stats(rules, &hands, samples, seed);
+        }
+        return;
+    }
+
     // Read our calibration file and split it by line
     let file = fs::read_to_string("data.txt").expect("data.txt not found or busy");
     let mut data: Vec<&str> = file.split('\n').collect();
     // Get rid of empty string at the end
-    while data.last().unwrap_or(&"a").len() == 0 {
+    while data.last().unwrap_or(&"a").is_empty() {
         data.pop();
     }
-    let mut hands = Vec::<(Hand, i32)>::new();
-    for datum in &data {
-        let bits: Vec<&str> = datum.split_whitespace().collect();
-        hands.push((parse_to_hand(bits[0]), bits[1].parse::<i32>().unwrap()));
-    }
-    hands.sort();
-    let mut sum = 0;
-    for i in 0..hands.len() {
-        println!("{:?}: {}", hands[i].0, (i + 1) as i32 * hands[i].1);
-        sum += (i + 1) as i32 * hands[i].1;
+    // --rules=<file> scores by a rules file instead of both the usual rule sets
+    let table = args.iter().any(|arg| arg == "--table");
+    let rule_sets: Vec<(&str, Rules)> = match rules_file {
+        Some(path) => vec![("Total", read_rules(path))],
+        None => vec![("Standard", Rules::standard()), ("Jokers", Rules::joker())],
+    };
+    for (name, rules) in &rule_sets {
+        if table {
+            print_table(&data, rules);
+        }
+        println!("{}: {}", name, total_winnings(&data, rules));
     }
-    println!("{}", sum);
 }